@@ -1,11 +1,17 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine as _};
 use clap::{Parser, ValueEnum};
-use inquire::{Text, Editor};
-use openai::{
-    chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole},
-    set_key,
-};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use inquire::{Editor, Text};
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -16,84 +22,478 @@ struct Args {
     /// OenAI API Key
     #[clap(long = "key", short = 'k')]
     key: Option<String>,
-    /// Model. now only "gpt-3.5-turbo" and "gpt-3.5-turbo-0301" supported.
-    /// default is "gpt-3.5-turbo"
-    #[clap(long = "model", short = 'm', value_enum, default_value = "gpt-3.5-turbo")]
-    model: Option<Model>,
-}
-
-#[derive(Debug, Eq, PartialEq, ValueEnum, Clone)]
-#[allow(non_camel_case_types)]
-enum Model {
-    #[clap(name = "gpt-4")]
-    Gpt_4,
-    #[clap(name = "gpt-4-0314")]
-    Gpt_4_0314,
-    #[clap(name = "gpt-4-32k")]
-    Gpt_4_32k,
-    #[clap(name = "gpt-4-32k-0314")]
-    Gpt_4_32k_0314,
-    #[clap(name = "gpt-3.5-turbo")]
-    Gpt_3_5_Turbo,
-    #[clap(name = "gpt-3.5-turbo-0301")]
-    Gpt_3_5_Turbo_0301,
-}
-
-impl Model {
-    fn as_str(&self) -> &'static str {
-        match self {
-            Self::Gpt_4 => "gpt-4",
-            Self::Gpt_4_0314 => "gpt-4-0314",
-            Self::Gpt_4_32k => "gpt-4-32k",
-            Self::Gpt_4_32k_0314 => "gpt-4-32k-0314",
-            Self::Gpt_3_5_Turbo => "gpt-3.5-turbo",
-            Self::Gpt_3_5_Turbo_0301 => "gpt-3.5-turbo-0301",
+    /// Model name, e.g. "gpt-4", "gpt-3.5-turbo", or any name the chosen provider accepts.
+    #[clap(long = "model", short = 'm', default_value = "gpt-3.5-turbo")]
+    model: String,
+    /// Backend to send completions to
+    #[clap(long = "provider", value_enum, default_value = "openai")]
+    provider: ProviderKind,
+    /// Stream the reply token-by-token instead of waiting for the full completion
+    #[clap(long = "stream", short = 's')]
+    stream: bool,
+    /// Path to the config file (default: the OS config dir's ferrite-translator/config.toml)
+    #[clap(long = "config")]
+    config: Option<PathBuf>,
+    /// Named role from the config file to seed the system prompt with
+    #[clap(long = "role", short = 'r')]
+    role: Option<String>,
+    /// Path to an image whose text should be OCR'd and translated (requires a vision-capable model)
+    #[clap(long = "image")]
+    image: Option<PathBuf>,
+    /// Translate a file (or piped stdin) non-interactively and exit, instead of starting the REPL
+    #[clap(long = "input")]
+    input: Option<PathBuf>,
+    /// Where to write batch output; defaults to stdout
+    #[clap(long = "output")]
+    output: Option<PathBuf>,
+    /// Name of a saved session to resume; starts with a fresh conversation if no session of that name exists
+    #[clap(long = "session")]
+    session: Option<String>,
+}
+
+/// Default token budget for vision requests, generous enough that OCR'd
+/// passages don't get truncated mid-sentence.
+const VISION_MAX_TOKENS: u32 = 1024;
+
+/// Upper bound on how many bytes of input go into a single batch translation
+/// request, so long documents stay under the model's context limit. This is
+/// a byte count, not a character count, so it under-counts the available
+/// budget for multi-byte input such as Japanese text.
+const BATCH_CHUNK_BYTE_LIMIT: usize = 4000;
+/// How many chunks get translated concurrently in batch mode.
+const BATCH_WORKER_LIMIT: usize = 4;
+
+/// Builds a `ChatCompletionMessage` with the fields we actually use set and
+/// the rest (function/tool call plumbing we don't use) left empty.
+fn new_message(role: ChatCompletionMessageRole, content: impl Into<String>) -> ChatCompletionMessage {
+    ChatCompletionMessage {
+        role,
+        content: Some(content.into()),
+        name: None,
+        function_call: None,
+        tool_call_id: None,
+        tool_calls: None,
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum ProviderKind {
+    /// The official OpenAI API
+    #[clap(name = "openai")]
+    OpenAi,
+    /// Any OpenAI-compatible endpoint (local servers, gateways, ...) reached via `base_url`
+    #[clap(name = "compatible")]
+    Compatible,
+}
+
+/// On-disk config, merged underneath whatever the user passes on the CLI.
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    api_key: Option<String>,
+    /// Custom base URL for OpenAI-compatible endpoints, e.g. a local proxy.
+    base_url: Option<String>,
+    /// HTTP(S) proxy to route requests through.
+    proxy: Option<String>,
+    /// Named system prompts the user can switch between via `--role`.
+    #[serde(default)]
+    roles: HashMap<String, String>,
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file at {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file at {}", path.display()))
+    }
+
+    fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("ferrite-translator")
+            .join("config.toml")
+    }
+}
+
+/// Session names become a file name under the sessions directory, so reject
+/// anything that could escape it (path separators, `..`, or a leading `.`).
+fn validate_session_name(name: &str) -> Result<()> {
+    let valid =
+        !name.is_empty() && !name.contains('/') && !name.contains('\\') && !name.starts_with('.');
+    if valid {
+        Ok(())
+    } else {
+        anyhow::bail!("Invalid session name `{name}`: must not be empty, contain `/` or `\\`, or start with `.`")
+    }
+}
+
+fn session_path(name: &str) -> Result<PathBuf> {
+    validate_session_name(name)?;
+    Ok(dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("ferrite-translator")
+        .join("sessions")
+        .join(format!("{name}.json")))
+}
+
+/// Serializes the conversation history to disk so it can be resumed with `load`.
+fn save_session(name: &str, messages: &[ChatCompletionMessage]) -> Result<()> {
+    let path = session_path(name)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create session directory at {}", parent.display())
+        })?;
+    }
+    let json = serde_json::to_string_pretty(messages)?;
+    fs::write(&path, json).with_context(|| format!("Failed to write session to {}", path.display()))
+}
+
+/// Restores a conversation history previously written by `save_session`.
+fn load_session(name: &str) -> Result<Vec<ChatCompletionMessage>> {
+    let path = session_path(name)?;
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read session file at {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse session file at {}", path.display()))
+}
+
+/// Default base URL for the official OpenAI API. `OpenAiProvider` sends all of
+/// its requests here, and `OpenAiCompatibleProvider` falls back to it when no
+/// `base_url` is configured.
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// A backend capable of completing a chat transcript. Implementations hold
+/// whatever credentials/model name they need, so the REPL loop stays
+/// oblivious to which backend is actually in use.
+#[async_trait]
+trait Provider {
+    async fn complete(&self, messages: &[ChatCompletionMessage]) -> Result<ChatCompletionMessage>;
+    /// Same as `complete`, but streams the reply token-by-token, printing
+    /// each delta as it arrives instead of waiting for the full completion.
+    async fn complete_stream(
+        &self,
+        messages: &[ChatCompletionMessage],
+    ) -> Result<ChatCompletionMessage>;
+    /// Sends a pre-built multi-part (text + image) request. `messages` are
+    /// raw JSON rather than `ChatCompletionMessage` because image content
+    /// isn't representable in that type.
+    async fn complete_vision(
+        &self,
+        messages: &[Value],
+        max_tokens: u32,
+    ) -> Result<ChatCompletionMessage>;
+}
+
+struct OpenAiProvider {
+    model: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Provider for OpenAiProvider {
+    async fn complete(&self, messages: &[ChatCompletionMessage]) -> Result<ChatCompletionMessage> {
+        complete_request(&self.client, OPENAI_BASE_URL, &self.api_key, &self.model, messages).await
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[ChatCompletionMessage],
+    ) -> Result<ChatCompletionMessage> {
+        stream_completion(&self.client, OPENAI_BASE_URL, &self.api_key, &self.model, messages).await
+    }
+
+    async fn complete_vision(
+        &self,
+        messages: &[Value],
+        max_tokens: u32,
+    ) -> Result<ChatCompletionMessage> {
+        vision_completion(
+            &self.client,
+            OPENAI_BASE_URL,
+            &self.api_key,
+            &self.model,
+            messages,
+            max_tokens,
+        )
+        .await
+    }
+}
+
+/// Targets any OpenAI-compatible endpoint (local servers, gateways, ...) via
+/// a user-supplied base URL instead of api.openai.com.
+struct OpenAiCompatibleProvider {
+    model: String,
+    api_key: String,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Provider for OpenAiCompatibleProvider {
+    async fn complete(&self, messages: &[ChatCompletionMessage]) -> Result<ChatCompletionMessage> {
+        complete_request(
+            &self.client,
+            &self.base_url,
+            &self.api_key,
+            &self.model,
+            messages,
+        )
+        .await
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[ChatCompletionMessage],
+    ) -> Result<ChatCompletionMessage> {
+        stream_completion(
+            &self.client,
+            &self.base_url,
+            &self.api_key,
+            &self.model,
+            messages,
+        )
+        .await
+    }
+
+    async fn complete_vision(
+        &self,
+        messages: &[Value],
+        max_tokens: u32,
+    ) -> Result<ChatCompletionMessage> {
+        vision_completion(
+            &self.client,
+            &self.base_url,
+            &self.api_key,
+            &self.model,
+            messages,
+            max_tokens,
+        )
+        .await
+    }
+}
+
+/// Shared non-streaming completion request used by both providers; only the
+/// base URL differs. Goes through `client` directly (rather than the `openai`
+/// crate) so a configured proxy is actually honored for every provider.
+async fn complete_request(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    messages: &[ChatCompletionMessage],
+) -> Result<ChatCompletionMessage> {
+    let response: Value = client
+        .post(format!("{base_url}/chat/completions"))
+        .bearer_auth(api_key)
+        .json(&json!({
+            "model": model,
+            "messages": messages,
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    let content = response["choices"][0]["message"]["content"]
+        .as_str()
+        .with_context(|| "Can't read completion output")?;
+
+    Ok(new_message(ChatCompletionMessageRole::Assistant, content))
+}
+
+/// Shared SSE-streaming request used by both providers; only the base URL
+/// (and thus which server actually receives the request) differs.
+async fn stream_completion(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    messages: &[ChatCompletionMessage],
+) -> Result<ChatCompletionMessage> {
+    let mut body = client
+        .post(format!("{base_url}/chat/completions"))
+        .bearer_auth(api_key)
+        .json(&json!({
+            "model": model,
+            "messages": messages,
+            "stream": true,
+        }))
+        .send()
+        .await?
+        .bytes_stream();
+
+    let mut content = String::new();
+    let mut buf = String::new();
+    'stream: while let Some(chunk) = body.next().await {
+        buf.push_str(&String::from_utf8_lossy(&chunk?));
+        while let Some(pos) = buf.find('\n') {
+            let line = buf[..pos].trim().to_string();
+            buf.drain(..=pos);
+            let Some(data) = line.strip_prefix("data: ") else {
+                continue;
+            };
+            if data == "[DONE]" {
+                break 'stream;
+            }
+            let event: Value = serde_json::from_str(data)?;
+            if let Some(delta) = event["choices"][0]["delta"]["content"].as_str() {
+                print!("{delta}");
+                std::io::stdout().flush().ok();
+                content.push_str(delta);
+            }
         }
     }
+    println!();
+
+    Ok(new_message(ChatCompletionMessageRole::Assistant, content))
+}
+
+/// Shared vision request used by both providers; only the base URL differs.
+async fn vision_completion(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    messages: &[Value],
+    max_tokens: u32,
+) -> Result<ChatCompletionMessage> {
+    let response: Value = client
+        .post(format!("{base_url}/chat/completions"))
+        .bearer_auth(api_key)
+        .json(&json!({
+            "model": model,
+            "max_tokens": max_tokens,
+            "messages": messages,
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    let content = response["choices"][0]["message"]["content"]
+        .as_str()
+        .with_context(|| "Can't read vision model output")?;
+
+    Ok(new_message(ChatCompletionMessageRole::Assistant, content))
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    let key = args.key.unwrap_or(
+    let config_path = args.config.clone().unwrap_or_else(Config::default_path);
+    let config = Config::load(&config_path)?;
+
+    let api_key = args.key.or(config.api_key.clone()).map(Ok).unwrap_or_else(|| {
         env::var("OPENAI_API_KEY")
-            .with_context(|| "You need to set API key to the `OPENAI_API_KEY`")?,
-    );
-    set_key(key);
+            .with_context(|| "You need to set API key to the `OPENAI_API_KEY`")
+    })?;
+
+    let role_prompt = match &args.role {
+        Some(name) => Some(
+            config
+                .roles
+                .get(name)
+                .cloned()
+                .with_context(|| format!("Role `{name}` not found in config file"))?,
+        ),
+        None => None,
+    };
 
     let mut messages = vec![
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::System,
-            content: args
-                .general
-                .unwrap_or(String::from("Plase translate the following statement from Japanese to English. The answer should be only the English stentences.")),
-            name: None,
-        },
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::System,
-            content: String::from(
-                "The user can reset the current state of the chat by inputting 'reset'.",
-            ),
-            name: None,
-        },
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::System,
-            content: String::from(
-                    "The user can activate the editor by entering 'v', allowing them to input multiple lines of prompts."
-                ),
-            name: None,
-        },
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::System,
-            content: String::from("To terminate, the user needs to input \"exit\"."),
-            name: None,
-        },
+        new_message(
+            ChatCompletionMessageRole::System,
+            args.general.or(role_prompt).unwrap_or(String::from(
+                "Plase translate the following statement from Japanese to English. The answer should be only the English stentences.",
+            )),
+        ),
+        new_message(
+            ChatCompletionMessageRole::System,
+            "The user can reset the current state of the chat by inputting 'reset'.",
+        ),
+        new_message(
+            ChatCompletionMessageRole::System,
+            "The user can activate the editor by entering 'v', allowing them to input multiple lines of prompts.",
+        ),
+        new_message(
+            ChatCompletionMessageRole::System,
+            "To terminate, the user needs to input \"exit\".",
+        ),
     ];
 
     let initial_state = messages.clone();
 
-    let model = args.model.unwrap().as_str();
+    if let Some(name) = &args.session {
+        let path = session_path(name)?;
+        messages = if path.exists() {
+            load_session(name)?
+        } else {
+            initial_state.clone()
+        };
+    }
+
+    let model = args.model;
+
+    let mut http_client_builder = reqwest::Client::builder();
+    if let Some(proxy) = &config.proxy {
+        http_client_builder = http_client_builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    let http_client = http_client_builder.build()?;
+
+    let provider: Box<dyn Provider> = match args.provider {
+        ProviderKind::OpenAi => Box::new(OpenAiProvider {
+            model: model.clone(),
+            api_key: api_key.clone(),
+            client: http_client.clone(),
+        }),
+        ProviderKind::Compatible => Box::new(OpenAiCompatibleProvider {
+            model: model.clone(),
+            api_key: api_key.clone(),
+            base_url: config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| OPENAI_BASE_URL.to_string()),
+            client: http_client.clone(),
+        }),
+    };
+
+    let batch_input = if let Some(path) = &args.input {
+        Some(
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read input file at {}", path.display()))?,
+        )
+    } else if !std::io::stdin().is_terminal() {
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf)?;
+        if buf.is_empty() {
+            None
+        } else {
+            Some(buf)
+        }
+    } else {
+        None
+    };
+
+    if let Some(text) = batch_input {
+        run_batch(
+            provider.as_ref(),
+            &messages[0],
+            &text,
+            args.output.as_deref(),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    if let Some(path) = &args.image {
+        let answer = ask_image(&mut messages, path, provider.as_ref()).await?;
+        println!("{:?}: {}", &answer.role, answer.content.as_deref().unwrap_or_default().trim());
+        messages.push(answer);
+    }
 
     loop {
         let input = Text::new("").prompt()?;
@@ -105,36 +505,187 @@ async fn main() -> Result<()> {
             "reset" => {
                 messages = Vec::from(&initial_state[..]);
             }
+            _ if input.starts_with("save ") => {
+                let name = input.trim_start_matches("save ").trim();
+                save_session(name, &messages)?;
+                println!("Saved session '{name}'.");
+            }
+            _ if input.starts_with("load ") => {
+                let name = input.trim_start_matches("load ").trim();
+                messages = load_session(name)?;
+                println!("Loaded session '{name}'.");
+            }
+            _ if input.starts_with("image ") => {
+                let path = PathBuf::from(input.trim_start_matches("image ").trim());
+                let answer = ask_image(&mut messages, &path, provider.as_ref()).await?;
+                println!("{:?}: {}", &answer.role, answer.content.as_deref().unwrap_or_default().trim());
+                messages.push(answer);
+            }
             "v" => {
                 let input = Editor::new("Prompt:").prompt()?;
-                let answer = ask(&mut messages, input, model).await?;
-                println!("{:?}: {}", &answer.role, &answer.content.trim());
+                let answer = if args.stream {
+                    ask_stream(&mut messages, input, provider.as_ref()).await?
+                } else {
+                    let answer = ask(&mut messages, input, provider.as_ref()).await?;
+                    println!("{:?}: {}", &answer.role, answer.content.as_deref().unwrap_or_default().trim());
+                    answer
+                };
                 messages.push(answer);
             }
             _ => {
-                let answer = ask(&mut messages, input, model).await?;
-                println!("{:?}: {}", &answer.role, &answer.content.trim());
+                let answer = if args.stream {
+                    ask_stream(&mut messages, input, provider.as_ref()).await?
+                } else {
+                    let answer = ask(&mut messages, input, provider.as_ref()).await?;
+                    println!("{:?}: {}", &answer.role, answer.content.as_deref().unwrap_or_default().trim());
+                    answer
+                };
                 messages.push(answer);
             }
         }
     }
 }
 
-async fn ask(messages: &mut Vec<ChatCompletionMessage>, input: String, model: &str) -> Result<ChatCompletionMessage> {
-    messages.push(ChatCompletionMessage {
-        role: ChatCompletionMessageRole::User,
-        content: input,
-        name: None,
-    });
-
-    let chat_completion = ChatCompletion::builder(model, messages.clone())
-        .create()
-        .await??;
-    let answer = chat_completion
-        .choices
-        .first()
-        .with_context(|| "Can't read ChatGPT output")?
-        .message
-        .clone();
-    Ok(answer)
+async fn ask(
+    messages: &mut Vec<ChatCompletionMessage>,
+    input: String,
+    provider: &dyn Provider,
+) -> Result<ChatCompletionMessage> {
+    messages.push(new_message(ChatCompletionMessageRole::User, input));
+
+    provider.complete(messages).await
+}
+
+/// Same as `ask`, but streams the completion token-by-token, printing each
+/// delta as it arrives instead of waiting for the full reply.
+async fn ask_stream(
+    messages: &mut Vec<ChatCompletionMessage>,
+    input: String,
+    provider: &dyn Provider,
+) -> Result<ChatCompletionMessage> {
+    messages.push(new_message(ChatCompletionMessageRole::User, input));
+
+    provider.complete_stream(messages).await
+}
+
+/// Reads an image from disk, base64-encodes it into a `data:` URL, and asks
+/// a vision-capable model to transcribe and translate any Japanese text in
+/// it. The outgoing request grows a structured, multi-part content list, but
+/// the conversation history only keeps an opaque `"[image: <path>]"` placeholder
+/// for that turn — so a saved-and-reloaded session can't re-send or inspect
+/// what was actually asked about the image.
+async fn ask_image(
+    messages: &mut Vec<ChatCompletionMessage>,
+    image_path: &Path,
+    provider: &dyn Provider,
+) -> Result<ChatCompletionMessage> {
+    let bytes = fs::read(image_path)
+        .with_context(|| format!("Failed to read image at {}", image_path.display()))?;
+    let mime = mime_guess::from_path(image_path).first_or_octet_stream();
+    let data_url = format!("data:{mime};base64,{}", BASE64_STANDARD.encode(&bytes));
+
+    let mut payload_messages: Vec<Value> = messages
+        .iter()
+        .map(|m| json!({ "role": m.role, "content": m.content }))
+        .collect();
+    payload_messages.push(json!({
+        "role": "user",
+        "content": [
+            { "type": "text", "text": "Transcribe the text in this image, then translate it from Japanese to English." },
+            { "type": "image_url", "image_url": { "url": data_url } },
+        ],
+    }));
+
+    messages.push(new_message(
+        ChatCompletionMessageRole::User,
+        format!("[image: {}]", image_path.display()),
+    ));
+
+    provider
+        .complete_vision(&payload_messages, VISION_MAX_TOKENS)
+        .await
+}
+
+/// Splits `text` into chunks no larger than `max_len` bytes, preferring to
+/// break on line boundaries so a chunk doesn't cut a sentence in half. A
+/// single line longer than `max_len` on its own is hard-split at `max_len`
+/// (on a UTF-8 char boundary) as a fallback, so no chunk ever exceeds the
+/// limit regardless of how the input is broken into lines.
+fn split_into_chunks(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for line in text.split_inclusive('\n') {
+        if !current.is_empty() && current.len() + line.len() > max_len {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if line.len() > max_len {
+            chunks.extend(hard_split(line, max_len));
+        } else {
+            current.push_str(line);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Splits `text` into pieces of at most `max_len` bytes, never cutting a
+/// UTF-8 character in half.
+fn hard_split(text: &str, max_len: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > max_len {
+        let mut split_at = max_len;
+        while !rest.is_char_boundary(split_at) {
+            split_at -= 1;
+        }
+        let (piece, remainder) = rest.split_at(split_at);
+        pieces.push(piece.to_string());
+        rest = remainder;
+    }
+    if !rest.is_empty() {
+        pieces.push(rest.to_string());
+    }
+    pieces
+}
+
+/// Translates a single batch chunk using only the system prompt plus the
+/// chunk itself, so each chunk is translated independently of the others.
+async fn translate_chunk(
+    provider: &dyn Provider,
+    system_prompt: &ChatCompletionMessage,
+    chunk: String,
+) -> Result<String> {
+    let messages = vec![
+        system_prompt.clone(),
+        new_message(ChatCompletionMessageRole::User, chunk),
+    ];
+    let answer = provider.complete(&messages).await?;
+    Ok(answer.content.unwrap_or_default())
+}
+
+/// Translates `text` non-interactively: splits it into chunks, translates
+/// them concurrently with a bounded worker pool, and reassembles the result
+/// in the original order before writing it to `output` (or stdout).
+async fn run_batch(
+    provider: &dyn Provider,
+    system_prompt: &ChatCompletionMessage,
+    text: &str,
+    output: Option<&Path>,
+) -> Result<()> {
+    let chunks = split_into_chunks(text, BATCH_CHUNK_BYTE_LIMIT);
+    let translated: Vec<String> = stream::iter(chunks)
+        .map(|chunk| translate_chunk(provider, system_prompt, chunk))
+        .buffered(BATCH_WORKER_LIMIT)
+        .try_collect()
+        .await?;
+    let result = translated.concat();
+
+    match output {
+        Some(path) => fs::write(path, &result)
+            .with_context(|| format!("Failed to write output to {}", path.display()))?,
+        None => println!("{result}"),
+    }
+    Ok(())
 }